@@ -3,18 +3,33 @@ use tauri::{
     tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent},
     Manager, WebviewUrl, WebviewWindowBuilder,
 };
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
-use std::sync::Mutex;
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
+use std::sync::Mutex;
 
 #[cfg(windows)]
 use windows::Win32::UI::WindowsAndMessaging::GetCursorPos;
 #[cfg(windows)]
 use windows::Win32::Foundation::POINT;
+#[cfg(windows)]
+use windows::Win32::Graphics::Gdi::{MonitorFromPoint, MONITOR_DEFAULTTONEAREST};
+#[cfg(windows)]
+use windows::Win32::UI::HiDpi::{
+    GetDpiForMonitor, SetProcessDpiAwarenessContext, DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2,
+    MDT_EFFECTIVE_DPI,
+};
+
+#[cfg(target_os = "macos")]
+use core_graphics::event::{CGEvent, CGEventSource, CGEventSourceStateID};
+
+#[cfg(target_os = "linux")]
+use std::sync::OnceLock;
 
 // 设置结构体
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Settings {
     pub screen_height_ratio: f64,
@@ -27,6 +42,14 @@ pub struct Settings {
     pub llm_model: String,
     pub character_setting: String,  // 角色设定
     pub reply_format: String,       // 回复格式
+    #[serde(default = "default_close_to_tray")]
+    pub close_to_tray: bool,        // 关闭主窗口时最小化到托盘而不是退出
+}
+
+// 旧版本 settings.json 里没有 close_to_tray 字段，反序列化时用这个默认值补齐，
+// 避免因为缺一个新字段就整份设置解析失败、被 load_settings_from_file 重置为默认值
+fn default_close_to_tray() -> bool {
+    true
 }
 
 // 注意：这里的默认值应与 src/settingsStore.ts 中的 DEFAULT_SETTINGS 保持一致
@@ -47,14 +70,66 @@ impl Default for Settings {
             // 这里使用空字符串，前端会检测并使用 TypeScript 的默认值
             character_setting: String::new(),
             reply_format: String::new(),
+            close_to_tray: true,
         }
     }
 }
 
+// 鼠标位置（物理像素）以及当前所在显示器的 DPI 缩放比例，
+// 供前端换算为逻辑坐标，避免高 DPI 显示器上出现偏移
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CursorPositionLogical {
+    pub x: i32,
+    pub y: i32,
+    pub scale: f64,
+}
+
+// 通用窗口配置，供前端声明式地打开任意窗口（设置、聊天记录、关于等），
+// 对应常用的 Tauri 窗口选项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowConfig {
+    pub label: String,
+    pub title: String,
+    pub url: String,
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    #[serde(default)]
+    pub center: bool,
+    #[serde(default = "default_true")]
+    pub resizable: bool,
+    #[serde(default = "default_true")]
+    pub decorations: bool,
+    #[serde(default)]
+    pub always_on_top: bool,
+    #[serde(default)]
+    pub visible_on_all_workspaces: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// 一条对话消息，结构与 OpenAI 兼容接口的 chat/completions 消息一致
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
 // 全局设置状态
 struct AppState {
     settings: Mutex<Settings>,
     settings_path: PathBuf,
+    tray_icon: Mutex<Option<tauri::tray::TrayIcon>>,
+    tray_alert_active: Mutex<bool>,
+    tray_normal_icon: tauri::image::Image<'static>,
+    tray_alert_icon: tauri::image::Image<'static>,
+    chat_aborts: Mutex<HashMap<String, futures::future::AbortHandle>>,
 }
 
 // 获取设置文件路径
@@ -88,12 +163,221 @@ fn get_settings(state: tauri::State<AppState>) -> Settings {
     state.settings.lock().unwrap().clone()
 }
 
-// 保存设置命令
+// 保存设置命令，保存后广播 settings-changed 事件，让所有窗口立即生效
 #[tauri::command]
-fn save_settings(state: tauri::State<AppState>, settings: Settings) -> Result<(), String> {
+fn save_settings(
+    app: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    settings: Settings,
+) -> Result<(), String> {
     let mut current = state.settings.lock().unwrap();
     *current = settings.clone();
-    save_settings_to_file(&state.settings_path, &settings)
+    save_settings_to_file(&state.settings_path, &settings)?;
+    let _ = app.emit("settings-changed", &settings);
+    Ok(())
+}
+
+// 监听设置文件的外部修改（例如用户手动编辑 settings.json），
+// 同步到内存状态并广播 settings-changed，保持和 UI 编辑一致的体验
+fn watch_settings_file(app: &tauri::AppHandle, settings_path: PathBuf) {
+    let app_handle = app.clone();
+    std::thread::spawn(move || {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut watcher = match notify::recommended_watcher(tx) {
+            Ok(watcher) => watcher,
+            Err(_) => return,
+        };
+        if watcher
+            .watch(&settings_path, notify::RecursiveMode::NonRecursive)
+            .is_err()
+        {
+            return;
+        }
+
+        for event in rx {
+            let Ok(event) = event else { continue };
+            if !event.kind.is_modify() {
+                continue;
+            }
+            let state = app_handle.state::<AppState>();
+            let settings = load_settings_from_file(&state.settings_path);
+            let mut current = state.settings.lock().unwrap();
+            // save_settings 已经把同样的内容写进了内存，这里再读到的是它自己触发的
+            // modify 事件，跳过避免重复广播 settings-changed
+            if *current == settings {
+                continue;
+            }
+            *current = settings.clone();
+            drop(current);
+            let _ = app_handle.emit("settings-changed", &settings);
+        }
+    });
+}
+
+// 开启/关闭托盘图标的提醒闪烁（有新回复但主窗口未显示时使用）
+#[tauri::command]
+fn set_tray_alert(app: tauri::AppHandle, state: tauri::State<AppState>, active: bool) {
+    *state.tray_alert_active.lock().unwrap() = active;
+    if !active {
+        clear_tray_alert(&app, &state);
+    }
+}
+
+// 立即将托盘图标恢复为正常状态并停止闪烁；托盘是 AppKit/Win32 的 UI 对象，
+// 所以 set_icon 必须通过 run_on_main_thread 派发到主线程执行
+fn clear_tray_alert(app: &tauri::AppHandle, state: &AppState) {
+    *state.tray_alert_active.lock().unwrap() = false;
+    let tray = state.tray_icon.lock().unwrap().clone();
+    let icon = state.tray_normal_icon.clone();
+    if let Some(tray) = tray {
+        let _ = app.run_on_main_thread(move || {
+            let _ = tray.set_icon(Some(icon));
+        });
+    }
+}
+
+// 打开（或聚焦已存在的）窗口，前端可据此声明式地创建聊天记录、关于、对话框等窗口，
+// 不必再为每个窗口在托盘菜单里复制一份创建逻辑
+#[tauri::command]
+fn open_window(app: tauri::AppHandle, config: WindowConfig) -> Result<(), String> {
+    if let Some(window) = app.get_webview_window(&config.label) {
+        window.show().map_err(|e| e.to_string())?;
+        window.set_focus().map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    let mut builder =
+        WebviewWindowBuilder::new(&app, &config.label, WebviewUrl::App(config.url.into()))
+            .title(&config.title)
+            .resizable(config.resizable)
+            .decorations(config.decorations)
+            .always_on_top(config.always_on_top)
+            .visible_on_all_workspaces(config.visible_on_all_workspaces);
+
+    if let (Some(width), Some(height)) = (config.width, config.height) {
+        builder = builder.inner_size(width, height);
+    }
+
+    builder = if config.center {
+        builder.center()
+    } else if let (Some(x), Some(y)) = (config.x, config.y) {
+        builder.position(x, y)
+    } else {
+        builder
+    };
+
+    builder.build().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// 取消一个正在进行的 chat_stream 请求（例如用户发送了新消息）
+#[tauri::command]
+fn cancel_chat_stream(state: tauri::State<AppState>, request_id: String) {
+    if let Some(handle) = state.chat_aborts.lock().unwrap().remove(&request_id) {
+        handle.abort();
+    }
+}
+
+// 在后端发起流式对话请求，带 API Key 的 HTTP 请求由 Rust 进程发出，不会出现在 webview 的网络面板中；
+// API Key 本身仍会随 get_settings 的返回值和 settings-changed 事件中的完整设置传到 webview。
+// 增量 token 通过 chat-token 事件推给前端，结束时发送 chat-done
+#[tauri::command]
+async fn chat_stream(
+    window: tauri::Window,
+    state: tauri::State<'_, AppState>,
+    request_id: String,
+    messages: Vec<ChatMessage>,
+) -> Result<(), String> {
+    let (base_url, api_key, model, character_setting) = {
+        let settings = state.settings.lock().unwrap();
+        (
+            settings.llm_base_url.clone(),
+            settings.llm_api_key.clone(),
+            settings.llm_model.clone(),
+            settings.character_setting.clone(),
+        )
+    };
+
+    let task_window = window.clone();
+    let task = async move {
+        let mut full_messages = Vec::with_capacity(messages.len() + 1);
+        full_messages.push(ChatMessage {
+            role: "system".to_string(),
+            content: character_setting,
+        });
+        full_messages.extend(messages);
+
+        let body = serde_json::json!({
+            "model": model,
+            "messages": full_messages,
+            "stream": true,
+        });
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!("{}/chat/completions", base_url.trim_end_matches('/')))
+            .bearer_auth(api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        // 上游返回非 2xx 时响应体是一段 JSON 错误信息而不是 SSE 流，
+        // 必须在这里识别出来，否则下面的 data: 解析循环会直接空转并误报 chat-done
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("LLM request failed ({status}): {body}"));
+        }
+
+        let mut stream = response.bytes_stream();
+        let mut buffer = String::new();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| e.to_string())?;
+            buffer.push_str(&String::from_utf8_lossy(&chunk));
+
+            while let Some(pos) = buffer.find('\n') {
+                let line = buffer[..pos].trim().to_string();
+                buffer.drain(..=pos);
+
+                let Some(data) = line.strip_prefix("data:") else {
+                    continue;
+                };
+                let data = data.trim();
+                if data == "[DONE]" {
+                    let _ = task_window.emit("chat-done", &request_id);
+                    return Ok(());
+                }
+                if let Ok(value) = serde_json::from_str::<serde_json::Value>(data) {
+                    if let Some(delta) = value["choices"][0]["delta"]["content"].as_str() {
+                        let _ = task_window.emit("chat-token", delta);
+                    }
+                }
+            }
+        }
+        let _ = task_window.emit("chat-done", &request_id);
+        Ok(())
+    };
+
+    let (abortable_task, abort_handle) = futures::future::abortable(task);
+    state
+        .chat_aborts
+        .lock()
+        .unwrap()
+        .insert(request_id.clone(), abort_handle);
+
+    let result = match abortable_task.await {
+        Ok(inner) => inner,
+        Err(futures::future::Aborted) => Ok(()),
+    };
+    state.chat_aborts.lock().unwrap().remove(&request_id);
+
+    // 无论请求建立失败、非 2xx 响应还是流式传输中途出错，都统一从这里广播 chat-error，
+    // 前端是按 chat-token/chat-done/chat-error 事件驱动的，不会去看 invoke() 的返回值
+    if let Err(ref message) = result {
+        let _ = window.emit("chat-error", message);
+    }
+    result
 }
 
 // 设置窗口是否忽略鼠标事件（点击穿透）
@@ -104,6 +388,79 @@ fn set_ignore_cursor_events(window: tauri::Window, ignore: bool) -> Result<(), S
         .map_err(|e| e.to_string())
 }
 
+// macOS 下通过 Core Graphics 的事件源读取全局鼠标位置
+#[cfg(target_os = "macos")]
+fn get_cursor_position_macos() -> Result<(i32, i32), String> {
+    let source = CGEventSource::new(CGEventSourceStateID::CombinedSessionState)
+        .map_err(|_| "Failed to create CGEventSource".to_string())?;
+    let event = CGEvent::new(source).map_err(|_| "Failed to create CGEvent".to_string())?;
+    let point = event.location();
+    Ok((point.x as i32, point.y as i32))
+}
+
+// Linux 下由全局输入监听线程持续写入的最近一次鼠标位置，
+// 在 get_cursor_position_linux 中直接读取，Wayland 会话（无 X11/XWayland 连接）下保持为空
+#[cfg(target_os = "linux")]
+static LAST_KNOWN_POINTER: OnceLock<Mutex<(i32, i32)>> = OnceLock::new();
+
+#[cfg(target_os = "linux")]
+static POINTER_LISTENER_STARTED: std::sync::Once = std::sync::Once::new();
+
+// XDisplay 句柄本身不是 Send，但这条连接只在监听线程内使用，因此手动标记为可发送
+#[cfg(target_os = "linux")]
+struct X11Display(*mut x11::xlib::Display);
+#[cfg(target_os = "linux")]
+unsafe impl Send for X11Display {}
+
+// 全局输入监听线程：打开一条常驻的 X11（或 XWayland）连接，以约 60Hz 轮询
+// XQueryPointer 并写入 LAST_KNOWN_POINTER，避免每次命令调用都重新连接/断开。
+// 纯 Wayland 会话下没有可用的 X 连接，线程直接退出，LAST_KNOWN_POINTER 保持未初始化
+#[cfg(target_os = "linux")]
+fn spawn_pointer_listener() {
+    use x11::xlib::{XDefaultRootWindow, XOpenDisplay, XQueryPointer};
+    std::thread::spawn(|| {
+        let raw_display = unsafe { XOpenDisplay(std::ptr::null()) };
+        if raw_display.is_null() {
+            return;
+        }
+        let display = X11Display(raw_display);
+        let root = unsafe { XDefaultRootWindow(display.0) };
+        loop {
+            let (mut root_x, mut root_y, mut win_x, mut win_y) = (0, 0, 0, 0);
+            let mut root_return = 0;
+            let mut child_return = 0;
+            let mut mask = 0;
+            let ok = unsafe {
+                XQueryPointer(
+                    display.0,
+                    root,
+                    &mut root_return,
+                    &mut child_return,
+                    &mut root_x,
+                    &mut root_y,
+                    &mut win_x,
+                    &mut win_y,
+                    &mut mask,
+                )
+            };
+            if ok != 0 {
+                let cache = LAST_KNOWN_POINTER.get_or_init(|| Mutex::new((root_x, root_y)));
+                *cache.lock().unwrap() = (root_x, root_y);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(16));
+        }
+    });
+}
+
+#[cfg(target_os = "linux")]
+fn get_cursor_position_linux() -> Result<(i32, i32), String> {
+    POINTER_LISTENER_STARTED.call_once(spawn_pointer_listener);
+    if let Some(cache) = LAST_KNOWN_POINTER.get() {
+        return Ok(*cache.lock().unwrap());
+    }
+    Err("Not implemented for this platform".to_string())
+}
+
 // 获取全局鼠标位置（屏幕坐标）
 #[tauri::command]
 fn get_cursor_position() -> Result<(i32, i32), String> {
@@ -115,26 +472,108 @@ fn get_cursor_position() -> Result<(i32, i32), String> {
         }
         Ok((point.x, point.y))
     }
-    #[cfg(not(windows))]
+    #[cfg(target_os = "macos")]
+    {
+        get_cursor_position_macos()
+    }
+    #[cfg(target_os = "linux")]
+    {
+        get_cursor_position_linux()
+    }
+    #[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
     {
         Err("Not implemented for this platform".to_string())
     }
 }
 
+// 获取鼠标位置及其所在显示器的 DPI 缩放比例（Windows 高 DPI 显示器校正）
+#[tauri::command]
+fn get_cursor_position_logical() -> Result<CursorPositionLogical, String> {
+    #[cfg(windows)]
+    {
+        let mut point = POINT::default();
+        unsafe {
+            GetCursorPos(&mut point).map_err(|e| e.to_string())?;
+        }
+        let scale = unsafe {
+            let hmon = MonitorFromPoint(point, MONITOR_DEFAULTTONEAREST);
+            let mut dpi_x: u32 = 96;
+            let mut dpi_y: u32 = 96;
+            GetDpiForMonitor(hmon, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y)
+                .map_err(|e| e.to_string())?;
+            dpi_x as f64 / 96.0
+        };
+        Ok(CursorPositionLogical {
+            x: point.x,
+            y: point.y,
+            scale,
+        })
+    }
+    #[cfg(not(windows))]
+    {
+        // macOS/Linux 上 Tauri 的坐标体系已经是逻辑像素，缩放比例恒为 1
+        let (x, y) = get_cursor_position()?;
+        Ok(CursorPositionLogical { x, y, scale: 1.0 })
+    }
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // 必须在创建任何顶层窗口（包括 tauri.conf.json 里配置的 main 窗口）之前设置，
+    // Windows 只在第一个窗口创建前接受这个调用，放进 setup() 里为时已晚
+    #[cfg(windows)]
+    unsafe {
+        SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2)
+            .expect("failed to set per-monitor DPI awareness");
+    }
+
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![set_ignore_cursor_events, get_cursor_position, get_settings, save_settings])
+        .invoke_handler(tauri::generate_handler![
+            set_ignore_cursor_events,
+            get_cursor_position,
+            get_cursor_position_logical,
+            get_settings,
+            save_settings,
+            set_tray_alert,
+            open_window,
+            chat_stream,
+            cancel_chat_stream
+        ])
         .setup(|app| {
-            // 初始化设置
+            // macOS 下仅作为菜单栏伴侣运行，不在 Dock 显示图标
+            #[cfg(target_os = "macos")]
+            app.set_activation_policy(tauri::ActivationPolicy::Accessory);
+
+            // 初始化设置；首次运行时 settings.json 还不存在，先落盘默认值，
+            // 否则下面的文件监听会因为路径不存在而直接失败退出
             let settings_path = get_settings_path(app.handle());
             let settings = load_settings_from_file(&settings_path);
+            if !settings_path.exists() {
+                let _ = save_settings_to_file(&settings_path, &settings);
+            }
+            watch_settings_file(app.handle(), settings_path.clone());
+
+            // 托盘提醒用的两个图标：正常图标复用窗口图标，闪烁图标为额外打包的资源，
+            // 找不到时退回正常图标，避免因缺少资源而启动失败
+            let tray_normal_icon = app.default_window_icon().unwrap().clone();
+            let tray_alert_icon = app
+                .path()
+                .resolve("icons/tray-alert.png", tauri::path::BaseDirectory::Resource)
+                .ok()
+                .and_then(|path| tauri::image::Image::from_path(path).ok())
+                .unwrap_or_else(|| tray_normal_icon.clone());
+
             app.manage(AppState {
                 settings: Mutex::new(settings),
                 settings_path,
+                tray_icon: Mutex::new(None),
+                tray_alert_active: Mutex::new(false),
+                tray_normal_icon,
+                tray_alert_icon,
+                chat_aborts: Mutex::new(HashMap::new()),
             });
-            
+
             // 创建托盘菜单
             let quit = MenuItem::with_id(app, "quit", "退出", true, None::<&str>)?;
             let show = MenuItem::with_id(app, "show", "显示/隐藏", true, None::<&str>)?;
@@ -159,25 +598,26 @@ pub fn run() {
                                 let _ = window.set_focus();
                             }
                         }
+                        clear_tray_alert(app, &app.state::<AppState>());
                     }
                     "settings" => {
-                        // 检查设置窗口是否已存在
-                        if let Some(window) = app.get_webview_window("settings") {
-                            let _ = window.show();
-                            let _ = window.set_focus();
-                        } else {
-                            // 创建新的设置窗口
-                            let _ = WebviewWindowBuilder::new(
-                                app,
-                                "settings",
-                                WebviewUrl::App("settings.html".into()),
-                            )
-                            .title("设置")
-                            .inner_size(450.0, 680.0)
-                            .resizable(false)
-                            .center()
-                            .build();
-                        }
+                        let _ = open_window(
+                            app.clone(),
+                            WindowConfig {
+                                label: "settings".to_string(),
+                                title: "设置".to_string(),
+                                url: "settings.html".to_string(),
+                                width: Some(450.0),
+                                height: Some(680.0),
+                                x: None,
+                                y: None,
+                                center: true,
+                                resizable: false,
+                                decorations: true,
+                                always_on_top: false,
+                                visible_on_all_workspaces: false,
+                            },
+                        );
                     }
                     _ => {}
                 })
@@ -197,10 +637,58 @@ pub fn run() {
                                 let _ = window.set_focus();
                             }
                         }
+                        clear_tray_alert(app, &app.state::<AppState>());
                     }
                 })
                 .build(app)?;
 
+            *app.state::<AppState>().tray_icon.lock().unwrap() = Some(_tray.clone());
+
+            // 后台线程每 500ms 决定一次是否切换托盘图标，在有新回复且主窗口未显示时闪烁提醒；
+            // 实际的 set_icon 调用通过 run_on_main_thread 派发到主线程执行，
+            // 托盘/菜单是 UI 对象，跨线程直接调用在 macOS/AppKit 上是未定义行为
+            let app_handle = app.handle().clone();
+            std::thread::spawn(move || {
+                let mut showing_alert = false;
+                loop {
+                    std::thread::sleep(std::time::Duration::from_millis(500));
+                    let state = app_handle.state::<AppState>();
+                    if !*state.tray_alert_active.lock().unwrap() {
+                        showing_alert = false;
+                        continue;
+                    }
+                    showing_alert = !showing_alert;
+                    let tray = state.tray_icon.lock().unwrap().clone();
+                    let icon = if showing_alert {
+                        state.tray_alert_icon.clone()
+                    } else {
+                        state.tray_normal_icon.clone()
+                    };
+                    if let Some(tray) = tray {
+                        let _ = app_handle.run_on_main_thread(move || {
+                            let _ = tray.set_icon(Some(icon));
+                        });
+                    }
+                }
+            });
+
+            // 关闭主窗口时默认最小化到托盘而不是退出（可在设置中关闭）
+            if let Some(main_window) = app.get_webview_window("main") {
+                let app_handle = app.handle().clone();
+                main_window.on_window_event(move |event| {
+                    if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+                        let state = app_handle.state::<AppState>();
+                        let close_to_tray = state.settings.lock().unwrap().close_to_tray;
+                        if close_to_tray {
+                            api.prevent_close();
+                            if let Some(window) = app_handle.get_webview_window("main") {
+                                let _ = window.hide();
+                            }
+                        }
+                    }
+                });
+            }
+
             Ok(())
         })
         .run(tauri::generate_context!())